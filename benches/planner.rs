@@ -0,0 +1,79 @@
+#[macro_use]
+extern crate criterion;
+extern crate dataframes;
+
+use criterion::{black_box, BenchmarkId, Criterion};
+use dataframes::{parse, Action, Query};
+
+const JOIN_EVERY: usize = 16;
+const GROUP_EVERY: usize = 40;
+
+fn names(width: usize, depth: usize) -> Vec<String> {
+    (0..width + depth / JOIN_EVERY).map(|i| format!("c{}", i)).collect()
+}
+
+/// A well-shaped plan `width` columns wide and `depth` steps deep: Maps
+/// and Filters over the existing columns, a Join adding a column every
+/// `JOIN_EVERY` steps, a Group every `GROUP_EVERY`, and a final Select
+/// that leaves every other column empty for the optimizer to remove.
+fn synthetic<'a>(names: &'a [String], width: usize, depth: usize) -> Query<'a> {
+    let mut steps = vec![names[..width].iter().map(|name| Action::Name(name)).collect::<Vec<_>>()];
+    let mut current = width;
+
+    for d in 1..depth {
+        let mut step = vec![Action::None; current];
+        if d % JOIN_EVERY == 0 {
+            step[0] = Action::Join(&names[current]);
+            step.push(Action::Name(&names[current]));
+            current += 1;
+        } else if d % GROUP_EVERY == 0 {
+            step[0] = Action::Group(0);
+        } else if d % 3 == 0 {
+            step[d % current] = Action::Filter;
+        } else {
+            step = vec![Action::Map; current];
+        }
+        steps.push(step);
+    };
+
+    steps.push((0..current).map(|i| if i % 2 == 0 { Action::Select } else { Action::Empty }).collect());
+    Query::try_new(steps).expect("synthetic plans are well shaped")
+}
+
+fn optimize_wide(c: &mut Criterion) {
+    let mut group = c.benchmark_group("optimize/wide");
+    for &width in &[8, 32, 128, 512] {
+        let names = names(width, 32);
+        let query = synthetic(&names, width, 32);
+        group.bench_with_input(BenchmarkId::from_parameter(width), &query, |b, query| {
+            b.iter(|| black_box(query.optimize()))
+        });
+    }
+    group.finish();
+}
+
+fn optimize_deep(c: &mut Criterion) {
+    let mut group = c.benchmark_group("optimize/deep");
+    for &depth in &[16, 64, 256, 1024] {
+        let names = names(8, depth);
+        let query = synthetic(&names, 8, depth);
+        group.bench_with_input(BenchmarkId::from_parameter(depth), &query, |b, query| {
+            b.iter(|| black_box(query.optimize()))
+        });
+    }
+    group.finish();
+}
+
+fn round_trip(c: &mut Criterion) {
+    let names = names(32, 128);
+    let query = synthetic(&names, 32, 128);
+    let optimized = query.optimize();
+    let grid = query.grid().to_string();
+
+    c.bench_function("parse", |b| b.iter(|| black_box(parse::parse_query(&grid).unwrap())));
+    c.bench_function("display", |b| b.iter(|| black_box(query.to_string())));
+    c.bench_function("diff", |b| b.iter(|| black_box(query.diff(&optimized))));
+}
+
+criterion_group!(benches, optimize_wide, optimize_deep, round_trip);
+criterion_main!(benches);
@@ -0,0 +1,283 @@
+use std::fmt;
+
+use {Action, Col, Query};
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// Step and column indices refer to the original plan for removals and
+/// moves, and to the new plan for additions and rewrites.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change<'a> {
+    RemovedCol(usize, Option<&'a str>),
+    AddedCol(usize, Option<&'a str>),
+    MovedStep(usize, usize),
+    RemovedStep(usize),
+    AddedStep(usize),
+    Rewritten { step: usize, col: usize, from: Action<'a>, to: Action<'a> },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diff<'a> {
+    pub changes: Vec<Change<'a>>,
+}
+
+impl<'a> Diff<'a> {
+    pub fn new(before: &Query<'a>, after: &Query<'a>) -> Diff<'a> {
+        let before_cols = all_cols(before);
+        let after_cols = all_cols(after);
+        let col_map = match_cols(&before_cols, &after_cols);
+
+        let mut changes = vec![];
+        for (i, col) in before_cols.iter().enumerate() {
+            if col_map[i].is_none() {
+                changes.push(Change::RemovedCol(i, col.name()))
+            }
+        };
+        for (j, col) in after_cols.iter().enumerate() {
+            if !col_map.contains(&Some(j)) {
+                changes.push(Change::AddedCol(j, col.name()))
+            }
+        };
+
+        let project = |query: &Query<'a>, step: usize, before_side: bool| -> Vec<Action<'a>> {
+            col_map.iter().enumerate().filter_map(|(i, j)| j.map(|j| (i, j))).map(|(i, j)| {
+                let index = if before_side { i } else { j };
+                cell(query, step, index)
+            }).collect()
+        };
+        let before_steps: Vec<_> = (0..before.steps.len()).map(|s| project(before, s, true)).collect();
+        let after_steps: Vec<_> = (0..after.steps.len()).map(|t| project(after, t, false)).collect();
+
+        let mut step_map: Vec<Option<usize>> = vec![None; before_steps.len()];
+        let mut used = vec![false; after_steps.len()];
+        for (s, cells) in before_steps.iter().enumerate() {
+            if let Some(t) = (0..after_steps.len()).find(|&t| !used[t] && after_steps[t] == *cells) {
+                used[t] = true;
+                step_map[s] = Some(t);
+            }
+        };
+
+        let matched: Vec<(usize, usize)> = step_map.iter().enumerate()
+            .filter_map(|(s, t)| t.map(|t| (s, t)))
+            .collect();
+        let in_place = increasing_run(&matched.iter().map(|&(_, t)| t).collect::<Vec<_>>());
+        for (k, &(s, t)) in matched.iter().enumerate() {
+            if !in_place.contains(&k) {
+                changes.push(Change::MovedStep(s, t))
+            }
+        };
+
+        let unmatched_before = (0..before_steps.len()).filter(|&s| step_map[s].is_none());
+        let mut unmatched_after = (0..after_steps.len()).filter(|&t| !used[t]);
+        for s in unmatched_before {
+            let t = match unmatched_after.next() {
+                Some(t) => t,
+                None => {
+                    changes.push(Change::RemovedStep(s));
+                    continue
+                },
+            };
+            for (i, j) in col_map.iter().enumerate().filter_map(|(i, j)| j.map(|j| (i, j))) {
+                let from = cell(before, s, i);
+                let to = cell(after, t, j);
+                if from != to {
+                    changes.push(Change::Rewritten { step: t, col: j, from, to })
+                }
+            }
+        };
+        for t in unmatched_after {
+            changes.push(Change::AddedStep(t))
+        };
+
+        Diff { changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Renders one change per line, ANSI-coloured when `color` is set.
+    pub fn render(&self, color: bool) -> String {
+        if self.changes.is_empty() {
+            return "no changes\n".to_string()
+        }
+        let mut out = String::new();
+        for change in &self.changes {
+            let (paint, line) = match *change {
+                Change::RemovedCol(i, name) => (RED, format!("- column {}{} removed", i, label(name))),
+                Change::AddedCol(j, name) => (GREEN, format!("+ column {}{} added", j, label(name))),
+                Change::MovedStep(s, t) => (YELLOW, format!("~ step {} moved to {}", s, t)),
+                Change::RemovedStep(s) => (RED, format!("- step {} removed", s)),
+                Change::AddedStep(t) => (GREEN, format!("+ step {} added", t)),
+                Change::Rewritten { step, col, ref from, ref to } => {
+                    (CYAN, format!("* step {}, column {}: {:?} -> {:?}", step, col, from, to))
+                },
+            };
+            if color {
+                out.push_str(&format!("{}{}{}\n", paint, line, RESET))
+            } else {
+                out.push_str(&format!("{}\n", line))
+            }
+        };
+        out
+    }
+}
+
+impl<'a> fmt::Display for Diff<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(false))
+    }
+}
+
+/// A step missing its trailing cells, as the optimizer leaves a raised
+/// filter, reads them as None.
+fn cell<'a>(query: &Query<'a>, step: usize, index: usize) -> Action<'a> {
+    query.steps[step].actions.get(index).cloned().unwrap_or(Action::None)
+}
+
+fn label(name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!(" ({})", name),
+        None => String::new(),
+    }
+}
+
+fn all_cols<'a>(query: &Query<'a>) -> Vec<Col<'a>> {
+    let width = query.steps.iter().map(|step| step.actions.len()).max().unwrap_or(0);
+    (0..width).map(|i| query.col(i)).collect()
+}
+
+/// Named columns pair up by name, unnamed ones by their relative order.
+fn match_cols(before: &[Col], after: &[Col]) -> Vec<Option<usize>> {
+    let mut map = vec![None; before.len()];
+    let mut used = vec![false; after.len()];
+    for (i, col) in before.iter().enumerate() {
+        if let Some(name) = col.name() {
+            if let Some(j) = (0..after.len()).find(|&j| !used[j] && after[j].name() == Some(name)) {
+                used[j] = true;
+                map[i] = Some(j);
+            }
+        }
+    };
+    let mut next = 0;
+    for (i, col) in before.iter().enumerate() {
+        if col.name().is_some() {
+            continue
+        }
+        if let Some(j) = (next..after.len()).find(|&j| !used[j] && after[j].name().is_none()) {
+            used[j] = true;
+            map[i] = Some(j);
+            next = j + 1;
+        }
+    };
+    map
+}
+
+/// Positions of a longest increasing subsequence, preferring earlier
+/// elements on ties so that the later of two swapped steps is the one
+/// reported as moved.
+fn increasing_run(values: &[usize]) -> Vec<usize> {
+    let mut lengths = vec![1; values.len()];
+    let mut previous = vec![None; values.len()];
+    for i in 0..values.len() {
+        for k in 0..i {
+            if values[k] < values[i] && lengths[k] + 1 > lengths[i] {
+                lengths[i] = lengths[k] + 1;
+                previous[i] = Some(k);
+            }
+        }
+    };
+
+    let longest = lengths.iter().cloned().max().unwrap_or(0);
+    let mut run = vec![];
+    let mut current = lengths.iter().position(|&length| length == longest);
+    while let Some(i) = current {
+        run.push(i);
+        current = previous[i];
+    };
+    run.reverse();
+    run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gen;
+
+    #[test]
+    fn diff_reports_removed_columns_and_moved_steps() {
+        let query = Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Map,       Action::Map],
+            vec![Action::None,      Action::Map],
+            vec![Action::Filter,    Action::None],
+            vec![Action::Select,    Action::Empty],
+            ]);
+        let diff = query.diff(&query.optimize());
+        assert_eq!(diff.changes, vec![
+            Change::RemovedCol(1, Some("b")),
+            Change::MovedStep(3, 1),
+        ]);
+        assert_eq!(diff.to_string(), "- column 1 (b) removed\n~ step 3 moved to 1\n");
+    }
+
+    #[test]
+    fn diff_reads_trimmed_cells_as_none() {
+        let query = Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Map],
+            vec![Action::Join("d"), Action::Name("d")],
+            vec![Action::Filter,    Action::None],
+            vec![Action::Select,    Action::Select],
+            ]);
+        let diff = query.diff(&query.optimize());
+        assert_eq!(diff.changes, vec![Change::MovedStep(3, 1)]);
+    }
+
+    #[test]
+    fn optimize_only_moves_steps_and_removes_columns() {
+        let names = gen::names(16);
+        let options = gen::PlanOptions { width: 5, depth: 12, join: 20, group: 10, filter: 30 };
+        for seed in 0..500 {
+            let query = gen::plan(&names, &options, &mut gen::Rng::new(seed));
+            for change in query.diff(&query.optimize()).changes {
+                match change {
+                    Change::MovedStep(..) | Change::RemovedCol(..) => {},
+                    other => panic!("seed {}: unexpected {:?}\n{}", seed, other, query),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn diff_reports_rewritten_actions() {
+        let before = Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Map,       Action::Filter],
+            ]);
+        let after = Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Map,       Action::Select],
+            vec![Action::Select,    Action::None],
+            ]);
+        let diff = before.diff(&after);
+        assert_eq!(diff.changes, vec![
+            Change::Rewritten { step: 1, col: 1, from: Action::Filter, to: Action::Select },
+            Change::AddedStep(2),
+        ]);
+        assert_eq!(diff.render(true).lines().next().unwrap(),
+                   "\x1b[36m* step 1, column 1: Filter -> Select\x1b[0m");
+    }
+
+    #[test]
+    fn identical_plans_have_no_changes() {
+        let query = Query::new(vec![vec![Action::Name("a")], vec![Action::Map]]);
+        let diff = query.diff(&query);
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "no changes\n");
+    }
+}
@@ -0,0 +1,162 @@
+use {Action, Query};
+
+/// Shape of a generated plan. `depth` counts every step, including the
+/// leading Name step and the trailing Select step. `join`, `group` and
+/// `filter` are the percentage chances of each kind of middle step; the
+/// remaining steps Map a random subset of columns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanOptions {
+    pub width: usize,
+    pub depth: usize,
+    pub join: usize,
+    pub group: usize,
+    pub filter: usize,
+}
+
+impl Default for PlanOptions {
+    fn default() -> PlanOptions {
+        PlanOptions { width: 4, depth: 8, join: 10, group: 5, filter: 25 }
+    }
+}
+
+/// A small xorshift generator, so that plans are reproducible from a seed
+/// without pulling in a dependency.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed ^ 0x9e37_79b9_7f4a_7c15 | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A number in `0..n`. Panics if `n` is zero, since the range is empty.
+    pub fn below(&mut self, n: usize) -> usize {
+        assert!(n > 0, "cannot pick a number below 0");
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+pub fn names(count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("c{}", i)).collect()
+}
+
+/// Generates a well-shaped plan over `names`. The first `width` names
+/// start the grid and each Join takes the next one; once they run out,
+/// no more Joins are generated. Panics if a non-empty plan is asked for
+/// with a `width` of zero, since Joins need a column to join on.
+pub fn plan<'a>(names: &'a [String], options: &PlanOptions, rng: &mut Rng) -> Query<'a> {
+    assert!(options.width <= names.len(), "not enough names for the plan's width");
+    if options.depth == 0 {
+        return Query::new(vec![])
+    }
+    assert!(options.width > 0, "plans need at least one column");
+
+    let mut steps = vec![names[..options.width].iter().map(|name| Action::Name(name)).collect::<Vec<_>>()];
+    let mut current = options.width;
+
+    for _ in 0..options.depth.saturating_sub(2) {
+        let roll = rng.below(100);
+        let mut step = vec![Action::None; current];
+        if roll < options.join && current < names.len() {
+            step[rng.below(current)] = Action::Join(&names[current]);
+            step.push(Action::Name(&names[current]));
+            current += 1;
+        } else if roll < options.join + options.group {
+            step[rng.below(current)] = Action::Group(0);
+        } else if roll < options.join + options.group + options.filter {
+            for cell in &mut step {
+                if rng.chance(30) {
+                    *cell = Action::Filter
+                }
+            };
+            step[rng.below(current)] = Action::Filter;
+        } else {
+            for cell in &mut step {
+                if rng.chance(50) {
+                    *cell = Action::Map
+                }
+            };
+        }
+        steps.push(step);
+    };
+
+    if options.depth > 1 {
+        steps.push((0..current).map(|_| {
+            if rng.chance(50) { Action::Select } else { Action::Empty }
+        }).collect());
+    }
+    Query::try_new(steps).expect("generated plans are well shaped")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validate;
+
+    fn options() -> PlanOptions {
+        PlanOptions { width: 5, depth: 12, join: 20, group: 10, filter: 30 }
+    }
+
+    #[test]
+    fn plans_are_reproducible_from_a_seed() {
+        let names = names(16);
+        let first = plan(&names, &options(), &mut Rng::new(7));
+        let second = plan(&names, &options(), &mut Rng::new(7));
+        assert_eq!(first, second);
+        assert_eq!(first.steps.len(), 12);
+        assert_ne!(first, plan(&names, &options(), &mut Rng::new(8)));
+    }
+
+    #[test]
+    #[should_panic(expected = "plans need at least one column")]
+    fn plans_need_a_column() {
+        let options = PlanOptions { width: 0, ..PlanOptions::default() };
+        plan(&names(4), &options, &mut Rng::new(7));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot pick a number below 0")]
+    fn below_zero_is_empty() {
+        Rng::new(7).below(0);
+    }
+
+    #[test]
+    fn optimize_keeps_generated_plans_well_shaped() {
+        let names = names(16);
+        for seed in 0..500 {
+            let query = plan(&names, &options(), &mut Rng::new(seed));
+            let optimized = query.optimize();
+            assert!(validate::check_shape(&optimized.steps).is_ok(), "seed {}:\n{}", seed, query);
+            if optimized.width() > 0 {
+                assert_eq!(optimized.steps.len(), query.steps.len());
+            }
+        }
+    }
+
+    #[test]
+    fn optimize_removes_exactly_the_empty_columns() {
+        let names = names(16);
+        for seed in 0..500 {
+            let query = plan(&names, &options(), &mut Rng::new(seed));
+            let kept: Vec<_> = query.cols().iter()
+                .filter(|col| !col.is_empty())
+                .map(|col| col.name())
+                .collect();
+            let optimized: Vec<_> = query.optimize().cols().iter().map(|col| col.name()).collect();
+            assert_eq!(optimized, kept, "seed {}:\n{}", seed, query);
+        }
+    }
+}
@@ -0,0 +1,631 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+pub mod diff;
+pub mod gen;
+pub mod lint;
+pub mod parse;
+pub mod snapshot;
+pub mod store;
+pub mod table;
+pub mod validate;
+
+use table::DisplayOptions;
+use validate::{Limits, ShapeError};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action<'a> {
+    Empty,
+    None,
+    Name(&'a str),
+    Select,
+    Map,
+    Filter,
+    Having,
+    Explode,
+    Group(u32),
+    Join(&'a str),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AnnotationError {
+    NoSuchStep(usize),
+    Blank,
+    Unwritable,
+}
+
+impl fmt::Display for AnnotationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AnnotationError::NoSuchStep(step) => write!(f, "there is no step {}", step),
+            AnnotationError::Blank => write!(f, "annotation is blank"),
+            AnnotationError::Unwritable => write!(f, "annotations cannot contain `#` or line breaks"),
+        }
+    }
+}
+
+impl Error for AnnotationError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step<'a> {
+    actions: Vec<Action<'a>>,
+    annotations: Vec<&'a str>,
+}
+
+impl<'a> Step<'a> {
+    fn new(actions: Vec<Action<'a>>) -> Step<'a> {
+        Step { actions, annotations: vec![] }
+    }
+
+    /// A step with a Having cell filters aggregates, so it is not a
+    /// candidate for raising even if it also has Filter cells.
+    fn is_filter(&self) -> bool {
+        self.actions.contains(&Action::Filter) && !self.actions.contains(&Action::Having)
+    }
+
+    fn widest_action_index(&self) -> Option<usize> {
+        for (i, action) in self.actions.iter().enumerate().rev() {
+            if *action != Action::None {
+                return Some(i)
+            }
+        };
+        None
+    }
+
+    fn touches(&self, action: &Action, other: &Step) -> bool {
+        self.actions.iter().zip(&other.actions).any(|(mine, theirs)| mine == action && *theirs != Action::None)
+    }
+
+    fn is_group(&self) -> bool {
+        for action in &self.actions {
+            if let Action::Group(_) = *action {
+                return true
+            }
+        };
+        false
+    }
+
+    fn is_join(&self) -> bool {
+        for action in &self.actions {
+            if let Action::Join(_) = *action {
+                return true
+            }
+        };
+        false
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Col<'a> {
+    index: usize,
+    actions: Vec<Action<'a>>,
+}
+
+impl<'a> Col<'a> {
+    fn new(index: usize, actions: Vec<Action<'a>>) -> Col<'a> {
+        Col { index, actions }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The steps from the one naming the column to the last one acting on
+    /// it.
+    pub fn steps(&self) -> Range<usize> {
+        let start = self.actions.iter()
+            .position(|action| matches!(*action, Action::Name(_)))
+            .unwrap_or(0);
+        let end = self.actions.iter()
+            .rposition(|action| *action != Action::None && *action != Action::Empty)
+            .map_or(start, |i| i + 1);
+        start..end.max(start)
+    }
+
+    /// Indexes of the columns this one reads in `query`: the column holding
+    /// the Join in the step that named it, and the key of every Group it is
+    /// aggregated under. Scans the whole grid, so it is only computed when
+    /// asked for.
+    pub fn dependencies(&self, query: &Query<'a>) -> Vec<usize> {
+        let start = match self.actions.iter().position(|action| matches!(*action, Action::Name(_))) {
+            Some(start) => start,
+            None => return vec![],
+        };
+
+        let index = self.index;
+        let mut dependencies = vec![];
+        for (s, step) in query.steps.iter().enumerate() {
+            for (i, action) in step.actions.iter().enumerate() {
+                match *action {
+                    Action::Join(_) if s == start && i != index => dependencies.push(i),
+                    Action::Group(_) if s > start && i != index && index < step.actions.len() => {
+                        dependencies.push(i)
+                    },
+                    _ => {},
+                }
+            }
+        };
+        dependencies.sort();
+        dependencies.dedup();
+        dependencies
+    }
+
+    fn is_empty(&self) -> bool {
+        let mut is_empty = false;
+        let mut is_used = false;
+        let mut seen_name = false;
+
+        for action in &self.actions {
+            match *action {
+                Action::Empty if seen_name && !is_used => is_empty = true,
+                Action::Name(_) => seen_name = true,
+                Action::Filter | Action::Having | Action::Explode => is_used = true,
+                Action::Join(_) => is_used = true,
+                _ => {},
+            }
+        };
+        is_empty
+    }
+
+    pub fn name(&self) -> Option<&'a str> {
+        self.actions.iter().filter_map(|action| match *action {
+            Action::Name(name) => Some(name),
+            _ => None,
+        }).next()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query<'a> {
+    steps: Vec<Step<'a>>,
+}
+
+impl<'a> Query<'a> {
+    pub fn new(step_vec: Vec<Vec<Action<'a>>>) -> Query<'a> {
+        let steps = step_vec.into_iter().map(Step::new).collect();
+        Query { steps }
+    }
+
+    /// Like `new`, but checks the grid's shape: widths never shrink, only
+    /// a Join step may widen the grid, and every column starts with a Name
+    /// that no other column uses, so no step is empty. Names cannot contain quotes, backslashes
+    /// or control characters, which the grid format has no escapes for.
+    pub fn try_new(step_vec: Vec<Vec<Action<'a>>>) -> Result<Query<'a>, ShapeError> {
+        Query::try_new_with_limits(step_vec, &Limits::default())
+    }
+
+    pub fn try_new_with_limits(step_vec: Vec<Vec<Action<'a>>>, limits: &Limits) -> Result<Query<'a>, ShapeError> {
+        limits.check(&step_vec)?;
+        let query = Query::new(step_vec);
+        validate::check_shape(&query.steps)?;
+        Ok(query)
+    }
+
+    pub fn width(&self) -> usize {
+        match self.steps.last() {
+            Some(step) => step.actions.len(),
+            None => 0
+        }
+    }
+
+    pub fn col(&self, index: usize) -> Col<'a> {
+        let actions = self.steps.iter().map(|step| {
+            match step.actions.get(index) {
+                Some(action) => action.clone(),
+                None => Action::Empty,
+            }
+        }).collect();
+        Col::new(index, actions)
+    }
+
+    /// Attaches a label or comment to a step. Annotations move with their
+    /// step through optimization and are written after it as `# ...`, so
+    /// they cannot contain `#` or line breaks. Surrounding whitespace is
+    /// trimmed.
+    pub fn annotate(&mut self, step: usize, annotation: &'a str) -> Result<(), AnnotationError> {
+        let annotation = annotation.trim();
+        if annotation.is_empty() {
+            return Err(AnnotationError::Blank)
+        }
+        if annotation.contains(['#', '\n', '\r']) {
+            return Err(AnnotationError::Unwritable)
+        }
+        match self.steps.get_mut(step) {
+            Some(step) => step.annotations.push(annotation),
+            None => return Err(AnnotationError::NoSuchStep(step)),
+        }
+        Ok(())
+    }
+
+    /// Panics if there is no step `step`, like indexing.
+    pub fn annotations(&self, step: usize) -> &[&'a str] {
+        &self.steps[step].annotations
+    }
+
+    pub fn col_by_name(&self, name: &str) -> Option<Col<'a>> {
+        self.cols().into_iter().find(|col| col.name() == Some(name))
+    }
+
+    pub fn cols(&self) -> Vec<Col<'a>> {
+        (0..self.width()).map(|i| {
+            self.col(i)
+        }).collect()
+    }
+
+    /// The plain grid layout read back by `parse::parse_query`.
+    pub fn grid<'q>(&'q self) -> Grid<'q, 'a> {
+        Grid { query: self }
+    }
+
+    pub fn display<'q>(&'q self, options: DisplayOptions) -> table::Table<'q, 'a> {
+        table::Table::new(self, options)
+    }
+
+    /// Writes the plan with a manifest header; read it back with
+    /// `store::SavedPlan::load`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        store::save(self, path)
+    }
+
+    /// The plan's grid with its columns renamed `c0`, `c1`, ... in order
+    /// of appearance and its annotations dropped, so that it can be
+    /// shared without leaking names. Reads back with `parse::parse_query`.
+    pub fn redacted(&self) -> String {
+        let mut names: Vec<&str> = vec![];
+        for action in self.steps.iter().flat_map(|step| &step.actions) {
+            match *action {
+                Action::Name(name) | Action::Join(name) if !names.contains(&name) => names.push(name),
+                _ => {},
+            }
+        };
+        let labels = gen::names(names.len());
+        let label = |name: &str| labels[names.iter().position(|&other| other == name).unwrap()].as_str();
+
+        let steps = self.steps.iter().map(|step| {
+            step.actions.iter().map(|action| match *action {
+                Action::Name(name) => Action::Name(label(name)),
+                Action::Join(name) => Action::Join(label(name)),
+                ref action => action.clone(),
+            }).collect()
+        }).collect();
+        Query::new(steps).grid().to_string()
+    }
+
+    pub fn diff(&self, other: &Query<'a>) -> diff::Diff<'a> {
+        diff::Diff::new(self, other)
+    }
+
+    /// Warnings about likely mistakes in a valid plan.
+    pub fn lint(&self) -> Vec<lint::Lint> {
+        lint::lint(self)
+    }
+
+    pub fn optimize(&self) -> Query<'a> {
+        let mut query = self.clone();
+
+        for (i, col) in query.cols().iter().enumerate().rev() {
+            if col.is_empty() {
+                query.remove_col(i)
+            }
+        };
+        // Every Join marks a column as used, so steps are only left empty
+        // when every column was removed.
+        if query.width() == 0 {
+            return Query::new(vec![])
+        }
+
+        let mut filter_anchor = 0;
+        for (i, step) in query.steps.clone().iter().enumerate() {
+            if step.is_group() {
+                filter_anchor = i
+            }
+
+            if step.is_filter() {
+                let widest = step.widest_action_index().unwrap();
+                let introduced = (0..i).find(|&j| query.steps[j].actions.len() > widest).unwrap_or(i);
+                let exploded = (0..i).rev().find(|&j| query.steps[j].touches(&Action::Explode, step)).unwrap_or(0);
+                let anchor = filter_anchor.max(introduced).max(exploded);
+                if anchor + 1 < i {
+                    query.raise_step(i, anchor);
+                    let above = query.steps[anchor].actions.len();
+                    query.steps[anchor + 1].actions.truncate(above)
+                }
+            }
+        };
+
+        query
+    }
+
+    fn remove_col(&mut self, index: usize) {
+        for step in &mut self.steps {
+            if index < step.actions.len() {
+                step.actions.remove(index);
+            }
+        }
+    }
+
+    fn raise_step(&mut self, index: usize, anchor: usize) {
+        let rows_to_move_up = (anchor + 2..index + 1).rev();
+        for i in rows_to_move_up {
+            self.steps.swap(i, i - 1)
+        }
+    }
+}
+
+impl<'a> fmt::Display for Query<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.display(DisplayOptions::default()))
+    }
+}
+
+pub struct Grid<'q, 'a: 'q> {
+    query: &'q Query<'a>,
+}
+
+impl<'q, 'a> fmt::Display for Grid<'q, 'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cells: Vec<Vec<String>> = self.query.steps.iter().map(|step| {
+            step.actions.iter().map(|action| format!("{:?}", action)).collect()
+        }).collect();
+
+        let mut widths = vec![];
+        for row in &cells {
+            for (i, cell) in row.iter().enumerate() {
+                if i == widths.len() {
+                    widths.push(0)
+                }
+                widths[i] = widths[i].max(cell.len())
+            }
+        };
+
+        for (row, step) in cells.iter().zip(&self.query.steps) {
+            let mut line: String = row.iter().zip(&widths)
+                .map(|(cell, width)| format!("{:<1$}  ", cell, width))
+                .collect();
+            for annotation in &step.annotations {
+                line.push_str(&format!("# {}  ", annotation))
+            };
+            writeln!(f, "{}", line.trim_end())?
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_select_column_from_query() {
+        let query = Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Join("d"), Action::Name("b")],
+            ]
+        );
+        assert_eq!(query.col(0).actions, vec![Action::Name("a"), Action::Join("d")]);
+        assert_eq!(query.col(1).actions, vec![Action::Empty, Action::Name("b")]);
+    }
+
+    #[test]
+    fn can_select_all_columns_from_query() {
+        let query = Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Join("d"), Action::Name("b")],
+            ]
+        );
+        assert_eq!(query.cols(), vec![
+            Col::new(0, vec![Action::Name("a"), Action::Join("d")]),
+            Col::new(1, vec![Action::Empty, Action::Name("b")]),
+        ])
+    }
+
+    #[test]
+    fn can_select_column_by_name() {
+        let query = Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Join("d"), Action::Map,       Action::Name("d")],
+            vec![Action::None,      Action::Group(0),  Action::None],
+            vec![Action::Select,    Action::None,      Action::None],
+            ]);
+        let d = query.col_by_name("d").unwrap();
+        assert_eq!(d.index(), 2);
+        assert_eq!(d.steps(), 1..2);
+        assert_eq!(d.dependencies(&query), vec![0, 1]);
+
+        let a = query.col_by_name("a").unwrap();
+        assert_eq!(a.steps(), 0..4);
+        assert_eq!(a.dependencies(&query), vec![1]);
+        assert!(query.col_by_name("e").is_none());
+
+        let query = Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Join("orders"), Action::Name("id"), Action::Name("total")],
+            ]);
+        assert_eq!(query.col_by_name("id").unwrap().dependencies(&query), vec![0]);
+        assert_eq!(query.col_by_name("total").unwrap().dependencies(&query), vec![0]);
+        assert!(query.col(0).dependencies(&query).is_empty());
+    }
+
+    #[test]
+    fn can_detect_empty_col() {
+        let col = |actions| Col::new(0, actions);
+        assert!(
+            !col(vec![Action::Name("a")]).is_empty());
+        assert!(
+            col(vec![Action::Empty, Action::Name("a"), Action::Empty]).is_empty());
+        assert!(
+            !col(vec![Action::Empty, Action::Name("a"), Action::Join("d"), Action::Empty]).is_empty())
+    }
+
+    #[test]
+    fn optimize_keeps_exploded_cols() {
+        let query = Query::new(vec![
+            vec![Action::Name("id"), Action::Name("tags")],
+            vec![Action::Map,        Action::Explode],
+            vec![Action::Select,     Action::Empty],
+            ]);
+        assert_eq!(query.optimize(), query)
+    }
+
+    #[test]
+    fn optimize_will_remove_an_empty_col() {
+        let query = Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Join("d"), Action::Name("b"), Action::Name("c")],
+            vec![Action::Select,    Action::Select,    Action::Empty],
+            ]);
+        assert_eq!(query.optimize(), Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Join("d"), Action::Name("b")],
+            vec![Action::Select,    Action::Select],
+            ]))
+    }
+
+    #[test]
+    fn optimize_will_move_filters_upwards() {
+        let query = Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Map],
+            vec![Action::Filter],
+            ]);
+        assert_eq!(query.optimize(), Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Filter],
+            vec![Action::Map],
+            ]))
+    }
+
+    #[test]
+    fn optimize_will_not_move_filters_above_the_join_adding_their_column() {
+        let query = Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Map],
+            vec![Action::Join("d"), Action::Name("d")],
+            vec![Action::Map,       Action::None],
+            vec![Action::None,      Action::Filter],
+            vec![Action::Select,    Action::Select],
+            ]);
+        assert_eq!(query.optimize(), Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Map],
+            vec![Action::Join("d"), Action::Name("d")],
+            vec![Action::None,      Action::Filter],
+            vec![Action::Map,       Action::None],
+            vec![Action::Select,    Action::Select],
+            ]))
+    }
+
+    #[test]
+    fn optimize_only_moves_filters_above_explodes_of_other_columns() {
+        let query = Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Explode,   Action::None],
+            vec![Action::None,      Action::Filter],
+            vec![Action::Filter,    Action::None],
+            ]);
+        assert_eq!(query.optimize(), Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::None,      Action::Filter],
+            vec![Action::Explode,   Action::None],
+            vec![Action::Filter,    Action::None],
+            ]))
+    }
+
+    #[test]
+    fn annotations_move_with_their_step() {
+        let mut query = Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Map],
+            vec![Action::Filter],
+            ]);
+        query.annotate(2, "from model orders").unwrap();
+        query.annotate(2, "owner: data").unwrap();
+        let optimized = query.optimize();
+        assert_eq!(optimized.annotations(1), &["from model orders", "owner: data"]);
+        assert!(optimized.annotations(2).is_empty());
+        assert_eq!(optimized.grid().to_string(), "Name(\"a\")\nFilter     # from model orders  # owner: data\nMap\n");
+    }
+
+    #[test]
+    fn optimize_never_moves_having_steps() {
+        let query = Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Group(0),  Action::None],
+            vec![Action::None,      Action::Map],
+            vec![Action::Filter,    Action::Having],
+            vec![Action::Select,    Action::Select],
+            ]);
+        assert_eq!(query.optimize(), query)
+    }
+
+    #[test]
+    fn redacted_renames_columns_consistently() {
+        let mut query = Query::new(vec![
+            vec![Action::Name("email"), Action::Name("ssn")],
+            vec![Action::Join("card"),  Action::Filter,      Action::Name("card")],
+            vec![Action::Select,        Action::Empty,       Action::Select],
+            ]);
+        query.annotate(1, "from model customers").unwrap();
+        assert_eq!(query.redacted(), "\
+Name(\"c0\")  Name(\"c1\")
+Join(\"c2\")  Filter      Name(\"c2\")
+Select      Empty       Select
+");
+        assert!(parse::parse_query(&query.redacted()).is_ok());
+    }
+
+    #[test]
+    fn optimize_trims_none_cells_from_raised_filters() {
+        let query = Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Map],
+            vec![Action::Join("d"), Action::Name("d")],
+            vec![Action::Filter,    Action::None],
+            vec![Action::Select,    Action::Select],
+            ]);
+        assert_eq!(query.optimize(), Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Filter],
+            vec![Action::Map],
+            vec![Action::Join("d"), Action::Name("d")],
+            vec![Action::Select,    Action::Select],
+            ]))
+    }
+
+    #[test]
+    fn optimize_removes_every_step_with_every_col() {
+        let query = Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Map,       Action::Map],
+            vec![Action::Empty,     Action::Empty],
+            ]);
+        assert_eq!(query.optimize(), Query::new(vec![]))
+    }
+
+    #[test]
+    fn optimize_removes_several_empty_cols() {
+        let query = Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b"), Action::Name("c"), Action::Name("d")],
+            vec![Action::Select,    Action::Empty,     Action::Select,    Action::Empty],
+            ]);
+        assert_eq!(query.optimize(), Query::new(vec![
+            vec![Action::Name("a"), Action::Name("c")],
+            vec![Action::Select,    Action::Select],
+            ]))
+    }
+
+    #[test]
+    fn step_can_find_the_widest_action() {
+        let step = Step::new(vec![
+            Action::None, Action::Filter, Action::Filter, Action::None,
+            ]);
+        assert_eq!(step.widest_action_index().unwrap(), 2);
+        let step = Step::new(vec![
+            Action::None, Action::Filter, Action::Map, Action::None,
+            ]);
+        assert_eq!(step.widest_action_index().unwrap(), 2)
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+
+use {Action, Query};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LintKind {
+    FilterAfterGroup(usize),
+    SelectAfterEmpty,
+    UnusedMap,
+}
+
+impl fmt::Display for LintKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LintKind::FilterAfterGroup(group) => {
+                write!(f, "filter after the Group at step {} may read aggregates; use Having", group)
+            },
+            LintKind::SelectAfterEmpty => write!(f, "column is selected after it was emptied"),
+            LintKind::UnusedMap => write!(f, "mapped column is never used and will be removed"),
+        }
+    }
+}
+
+/// A likely mistake at `step`, `col`. Unlike a `ShapeError`, the plan is
+/// still valid.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lint {
+    pub step: usize,
+    pub col: usize,
+    pub kind: LintKind,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "step {}, column {}: {}", self.step, self.col, self.kind)
+    }
+}
+
+pub fn lint(query: &Query) -> Vec<Lint> {
+    let mut lints = vec![];
+
+    let mut group = None;
+    for (i, step) in query.steps.iter().enumerate() {
+        if let Some(group) = group {
+            if let Some(col) = step.actions.iter().position(|action| *action == Action::Filter) {
+                lints.push(Lint { step: i, col, kind: LintKind::FilterAfterGroup(group) })
+            }
+        }
+        if step.is_group() {
+            group = Some(i)
+        }
+    };
+
+    for (col, column) in query.cols().iter().enumerate() {
+        let mut emptied = false;
+        for (i, action) in column.actions.iter().enumerate() {
+            match *action {
+                Action::Empty if column.steps().start < i => emptied = true,
+                Action::Select if emptied => {
+                    lints.push(Lint { step: i, col, kind: LintKind::SelectAfterEmpty })
+                },
+                Action::Map if column.is_empty() => {
+                    lints.push(Lint { step: i, col, kind: LintKind::UnusedMap })
+                },
+                _ => {},
+            }
+        }
+    };
+
+    lints.sort_by_key(|lint| (lint.step, lint.col));
+    lints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lint_warns_about_likely_mistakes() {
+        let query = Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b"), Action::Name("c")],
+            vec![Action::Map,       Action::Map,       Action::Map],
+            vec![Action::Group(0),  Action::None,      Action::Empty],
+            vec![Action::None,      Action::Filter,    Action::Select],
+            vec![Action::Select,    Action::Select,    Action::Empty],
+            ]);
+        assert_eq!(query.lint(), vec![
+            Lint { step: 1, col: 2, kind: LintKind::UnusedMap },
+            Lint { step: 3, col: 1, kind: LintKind::FilterAfterGroup(2) },
+            Lint { step: 3, col: 2, kind: LintKind::SelectAfterEmpty },
+        ]);
+        assert_eq!(query.lint()[1].to_string(),
+                   "step 3, column 1: filter after the Group at step 2 may read aggregates; use Having");
+    }
+
+    #[test]
+    fn lint_accepts_a_clean_plan() {
+        let query = Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Filter,    Action::Map],
+            vec![Action::Group(0),  Action::None],
+            vec![Action::Select,    Action::Select],
+            ]);
+        assert!(query.lint().is_empty());
+    }
+}
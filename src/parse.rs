@@ -0,0 +1,360 @@
+use std::error::Error;
+use std::fmt;
+
+use validate::{Limits, ShapeError};
+use {Action, Query};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(line: usize, column: usize, message: String) -> ParseError {
+        ParseError { line, column, message }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Parses the grid format written by `Query::grid`: one step
+/// per line, actions separated by whitespace. Blank lines and lines
+/// starting with `#` are skipped. The grid must pass `Query::try_new`'s
+/// shape checks.
+///
+/// Text after a `#` outside a quoted name annotates the step; several
+/// annotations are separated by further `#`s.
+///
+/// A step may also be written as a template, `Map for "price_*"`, which
+/// applies the action to every column whose name matches the pattern and
+/// `None` to the rest. `*` in a pattern matches any run of characters.
+pub fn parse_query<'a>(input: &'a str) -> Result<Query<'a>, ParseError> {
+    parse_query_with_limits(input, &Limits::default())
+}
+
+/// Stops at the first step over `limits`, before reading the rest of
+/// the input, and at the first action over `max_cols`, before reading
+/// the rest of its line.
+pub fn parse_query_with_limits<'a>(input: &'a str, limits: &Limits) -> Result<Query<'a>, ParseError> {
+    let mut steps = vec![];
+    let mut positions = vec![];
+    let mut names = vec![];
+    let mut annotations = vec![];
+    let locate = |positions: &[(usize, usize, Vec<usize>)], err: ShapeError| {
+        let (line, end, ref columns) = positions[err.step];
+        let column = columns.get(err.col).cloned().unwrap_or(end);
+        ParseError::new(line, column, err.kind.to_string())
+    };
+
+    for (i, line) in input.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue
+        }
+        let (line, step_annotations) = split_annotations(line);
+        let (actions, columns) = parse_step(line, i + 1, &names, steps.len(), limits)?;
+        positions.push((i + 1, line.len() + 1, columns));
+        annotations.push(step_annotations);
+        for action in actions.iter().skip(names.len()) {
+            names.push(match *action {
+                Action::Name(name) => name,
+                _ => "",
+            })
+        };
+        steps.push(actions);
+    };
+
+    let mut query = Query::try_new(steps).map_err(|err| locate(&positions, err))?;
+    for (step, step_annotations) in annotations.into_iter().enumerate() {
+        query.steps[step].annotations = step_annotations
+    };
+    Ok(query)
+}
+
+/// Splits a step line at its first `#` outside quotes, into the actions
+/// and the `#`-separated annotations after them.
+fn split_annotations(line: &str) -> (&str, Vec<&str>) {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => {
+                let annotations = line[i + 1..].split('#')
+                    .map(|annotation| annotation.trim())
+                    .filter(|annotation| !annotation.is_empty())
+                    .collect();
+                return (&line[..i], annotations)
+            },
+            _ => {},
+        }
+    };
+    (line, vec![])
+}
+
+/// Parses step number `step`, failing as soon as it breaks `limits`.
+fn parse_step<'a>(line: &'a str, line_number: usize, names: &[&'a str], step: usize, limits: &Limits)
+                  -> Result<(Vec<Action<'a>>, Vec<usize>), ParseError> {
+    let over_limits = |column: usize, width: usize| {
+        limits.check_step(step, width).map_err(|err| ParseError::new(line_number, column, err.kind.to_string()))
+    };
+    over_limits(line.len() - line.trim_start().len() + 1, 0)?;
+
+    let mut actions = vec![];
+    let mut columns = vec![];
+    let mut rest = line;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return Ok((actions, columns))
+        }
+        let column = line.len() - rest.len() + 1;
+        over_limits(column, actions.len() + 1)?;
+        let (action, remaining) = parse_action(rest)
+            .map_err(|message| ParseError::new(line_number, column, message))?;
+
+        let after = remaining.trim_start();
+        if actions.is_empty() && after.starts_with("for") && after[3..].starts_with(char::is_whitespace) {
+            over_limits(column, names.len())?;
+            return expand_template(action, &after[3..], names)
+                .map(|actions| {
+                    let columns = vec![column; actions.len()];
+                    (actions, columns)
+                })
+                .map_err(|message| ParseError::new(line_number, column, message))
+        }
+        actions.push(action);
+        columns.push(column);
+        rest = remaining;
+    }
+}
+
+fn expand_template<'a>(action: Action<'a>, input: &'a str, names: &[&'a str]) -> Result<Vec<Action<'a>>, String> {
+    match action {
+        Action::Name(_) | Action::Join(_) => {
+            return Err(format!("{:?} cannot be applied to a pattern", action))
+        },
+        _ => {},
+    }
+    let (pattern, rest) = parse_string(input.trim_start())?;
+    if !rest.trim().is_empty() {
+        return Err(format!("unexpected `{}` after the pattern", first_token(rest)))
+    }
+
+    let actions: Vec<Action> = names.iter().map(|name| {
+        if glob_matches(pattern, name) { action.clone() } else { Action::None }
+    }).collect();
+    if !actions.contains(&action) {
+        return Err(format!("pattern \"{}\" matches no columns", pattern))
+    }
+    Ok(actions)
+}
+
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, rest) = parts.split_first().unwrap();
+    if !name.starts_with(first) {
+        return false
+    }
+    let (last, middle) = match rest.split_last() {
+        Some(split) => split,
+        None => return name == *first,
+    };
+
+    let mut remaining = &name[first.len()..];
+    for part in middle {
+        match remaining.find(part) {
+            Some(i) => remaining = &remaining[i + part.len()..],
+            None => return false,
+        }
+    };
+    remaining.ends_with(last)
+}
+
+fn parse_action<'a>(input: &'a str) -> Result<(Action<'a>, &'a str), String> {
+    let ident_end = input.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(input.len());
+    let (ident, rest) = input.split_at(ident_end);
+
+    let (action, rest) = match ident {
+        "Empty" => (Action::Empty, rest),
+        "None" => (Action::None, rest),
+        "Select" => (Action::Select, rest),
+        "Map" => (Action::Map, rest),
+        "Filter" => (Action::Filter, rest),
+        "Having" => (Action::Having, rest),
+        "Explode" => (Action::Explode, rest),
+        "Name" => {
+            let (name, rest) = parse_argument(rest, parse_string)?;
+            (Action::Name(name), rest)
+        },
+        "Join" => {
+            let (name, rest) = parse_argument(rest, parse_string)?;
+            (Action::Join(name), rest)
+        },
+        "Group" => {
+            let (id, rest) = parse_argument(rest, parse_u32)?;
+            (Action::Group(id), rest)
+        },
+        "" => return Err(format!("expected an action, found `{}`", first_token(input))),
+        _ => return Err(format!("unknown action `{}`", ident)),
+    };
+
+    match rest.chars().next() {
+        Some(c) if !c.is_whitespace() => Err(format!("unexpected `{}` after `{}`", c, ident)),
+        _ => Ok((action, rest)),
+    }
+}
+
+fn parse_argument<'a, T, F>(input: &'a str, parse: F) -> Result<(T, &'a str), String>
+    where F: Fn(&'a str) -> Result<(T, &'a str), String>
+{
+    if !input.starts_with('(') {
+        return Err("expected `(`".to_string())
+    }
+    let (value, rest) = parse(&input[1..])?;
+    if !rest.starts_with(')') {
+        return Err("expected `)`".to_string())
+    }
+    Ok((value, &rest[1..]))
+}
+
+fn parse_string(input: &str) -> Result<(&str, &str), String> {
+    if !input.starts_with('"') {
+        return Err("expected a quoted name".to_string())
+    }
+    let body = &input[1..];
+    match body.find(['"', '\\']) {
+        Some(end) if body[end..].starts_with('"') => Ok((&body[..end], &body[end + 1..])),
+        Some(_) => Err("escape sequences are not supported in names".to_string()),
+        None => Err("unterminated name".to_string()),
+    }
+}
+
+fn parse_u32(input: &str) -> Result<(u32, &str), String> {
+    let end = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    match input[..end].parse() {
+        Ok(value) => Ok((value, &input[end..])),
+        Err(_) => Err(format!("expected a group id, found `{}`", first_token(input))),
+    }
+}
+
+fn first_token(input: &str) -> &str {
+    input.split_whitespace().next().unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_a_query_grid() {
+        let query = parse_query("
+            Name(\"a\")  Name(\"b\")
+            # joins widen the grid
+            Join(\"d\")  Filter     Name(\"d\")
+            Group(0)   Select     Empty
+        ").unwrap();
+        assert_eq!(query, Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Join("d"), Action::Filter,    Action::Name("d")],
+            vec![Action::Group(0),  Action::Select,    Action::Empty],
+            ]))
+    }
+
+    #[test]
+    fn parse_round_trips_display_output() {
+        let query = Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Map,       Action::None],
+            vec![Action::Join("d"), Action::Filter,    Action::Name("d")],
+            ]);
+        assert_eq!(parse_query(&query.grid().to_string()).unwrap(), query)
+    }
+
+    #[test]
+    fn parse_reads_step_annotations() {
+        let query = parse_query("
+            Name(\"a#1\")  Name(\"b\")  # from model orders
+            Map for \"*\"  # cleanup # owner: data
+            Filter       None
+        ").unwrap();
+        assert_eq!(query.col(0).name(), Some("a#1"));
+        assert_eq!(query.annotations(0), &["from model orders"]);
+        assert_eq!(query.annotations(1), &["cleanup", "owner: data"]);
+        assert!(query.annotations(2).is_empty());
+        assert_eq!(parse_query(&query.grid().to_string()).unwrap(), query)
+    }
+
+    #[test]
+    fn parse_reports_the_position_of_bad_actions() {
+        let err = parse_query("Name(\"a\")\nMap  Mop").unwrap_err();
+        assert_eq!(err, ParseError::new(2, 6, "unknown action `Mop`".to_string()));
+
+        let err = parse_query("Group(x)").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 1));
+        assert_eq!(err.message, "expected a group id, found `x)`");
+
+        let err = parse_query("Name(\"a)").unwrap_err();
+        assert_eq!(err.message, "unterminated name");
+    }
+
+    #[test]
+    fn parse_expands_templates_against_column_names() {
+        let query = parse_query("
+            Name(\"price_a\")  Name(\"id\")  Name(\"price_b\")
+            Map for \"price_*\"
+            Join(\"d\")        None        None           Name(\"price_d\")
+            Select for \"*\"
+        ").unwrap();
+        assert_eq!(query, Query::new(vec![
+            vec![Action::Name("price_a"), Action::Name("id"), Action::Name("price_b")],
+            vec![Action::Map,             Action::None,       Action::Map],
+            vec![Action::Join("d"),       Action::None,       Action::None,  Action::Name("price_d")],
+            vec![Action::Select,          Action::Select,     Action::Select, Action::Select],
+            ]))
+    }
+
+    #[test]
+    fn parse_rejects_templates_that_match_nothing() {
+        let err = parse_query("Name(\"a\")\n  Filter for \"b*\"").unwrap_err();
+        assert_eq!(err, ParseError::new(2, 3, "pattern \"b*\" matches no columns".to_string()));
+
+        let err = parse_query("Name(\"a\")\nJoin(\"d\") for \"*\"").unwrap_err();
+        assert_eq!(err.message, "Join(\"d\") cannot be applied to a pattern");
+    }
+
+    #[test]
+    fn glob_matches_stars_anywhere() {
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("a*c", "abbc"));
+        assert!(glob_matches("*_id", "customer_id"));
+        assert!(glob_matches("a*b*c", "aXbYc"));
+        assert!(!glob_matches("a*b*c", "aXcYb"));
+        assert!(!glob_matches("id", "ids"));
+        assert!(!glob_matches("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn parse_stops_at_the_first_step_over_the_limits() {
+        let limits = Limits { max_steps: Some(1), ..Limits::default() };
+        let err = parse_query_with_limits("Name(\"a\")\nMap\nMop", &limits).unwrap_err();
+        assert_eq!(err, ParseError::new(2, 1, "plan exceeds the limit of 1 steps".to_string()));
+
+        let limits = Limits { max_cols: Some(2), ..Limits::default() };
+        let err = parse_query_with_limits("Name(\"a\")  Name(\"b\")  Name(\"c\")  Mop", &limits).unwrap_err();
+        assert_eq!(err, ParseError::new(1, 23, "step exceeds the limit of 2 columns".to_string()));
+    }
+
+    #[test]
+    fn parse_reports_the_position_of_shape_errors() {
+        let err = parse_query("Name(\"a\")\n\nMap  Name(\"b\")").unwrap_err();
+        assert_eq!(err, ParseError::new(3, 6, "only a Join step can add columns".to_string()));
+    }
+}
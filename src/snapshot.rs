@@ -0,0 +1,200 @@
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use parse;
+use Query;
+
+pub const INPUT: &str = "# input";
+pub const OPTIMIZED: &str = "# optimized";
+
+/// Renders a plan and its optimization as two grid sections, each of
+/// which `parse::parse_query` reads back.
+pub fn render(query: &Query) -> String {
+    format!("{}\n{}{}\n{}", INPUT, query.grid(), OPTIMIZED, query.optimize().grid())
+}
+
+/// Splits a snapshot into its input and optimized sections, each started
+/// by a line holding only its marker.
+pub fn sections(text: &str) -> Result<(&str, &str), String> {
+    let input = marker(text, INPUT)?;
+    let optimized = marker(text, OPTIMIZED)?;
+    if optimized.0 < input.0 {
+        return Err(format!("`{}` must come before `{}`", INPUT, OPTIMIZED))
+    }
+    Ok((&text[input.1..optimized.0], &text[optimized.1..]))
+}
+
+/// The start and end offsets of the first line that is exactly `marker`.
+fn marker(text: &str, marker: &str) -> Result<(usize, usize), String> {
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim() == marker {
+            return Ok((offset, offset + line.len()))
+        }
+        offset += line.len();
+    };
+    Err(format!("missing `{}` section", marker))
+}
+
+/// Re-optimizes the input section of the snapshot at `path` and compares
+/// the result against the file.
+pub fn check<P: AsRef<Path>>(path: P) -> Result<(), String> {
+    let path = path.as_ref();
+    let expected = read(path)?;
+    let (input, _) = sections(&expected).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let query = parse::parse_query(input).map_err(|err| format!("{}: {}", path.display(), err))?;
+    compare(path, &expected, &render(&query), updating())
+}
+
+/// Compares `query` against the snapshot at `path`, which need not exist
+/// yet when updating.
+pub fn assert_snapshot<P: AsRef<Path>>(path: P, query: &Query) {
+    assert_snapshot_with(path.as_ref(), query, updating())
+}
+
+fn assert_snapshot_with(path: &Path, query: &Query, update: bool) {
+    let expected = if path.exists() { read(path).unwrap() } else { String::new() };
+    if let Err(message) = compare(path, &expected, &render(query), update) {
+        panic!("{}", message)
+    }
+}
+
+fn updating() -> bool {
+    env::var_os("UPDATE_SNAPSHOTS").is_some()
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(text)
+}
+
+/// Rewrites the snapshot instead of failing when `update` is set, which
+/// the public helpers take from `UPDATE_SNAPSHOTS`.
+fn compare(path: &Path, expected: &str, actual: &str, update: bool) -> Result<(), String> {
+    if expected == actual {
+        return Ok(())
+    }
+    if update {
+        return File::create(path)
+            .and_then(|mut file| file.write_all(actual.as_bytes()))
+            .map_err(|err| format!("{}: {}", path.display(), err))
+    }
+    Err(format!("{} does not match (rerun with UPDATE_SNAPSHOTS=1 to accept):\n{}",
+                path.display(), line_diff(expected, actual)))
+}
+
+/// A line diff from the longest common subsequence of the two texts;
+/// `-` lines are only in `expected`, `+` lines only in `actual`.
+pub fn line_diff(expected: &str, actual: &str) -> String {
+    let before: Vec<&str> = expected.lines().collect();
+    let after: Vec<&str> = actual.lines().collect();
+
+    let mut common = vec![vec![0; after.len() + 1]; before.len() + 1];
+    for i in (0..before.len()).rev() {
+        for j in (0..after.len()).rev() {
+            common[i][j] = if before[i] == after[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            }
+        }
+    };
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < before.len() || j < after.len() {
+        if i < before.len() && j < after.len() && before[i] == after[j] {
+            out.push_str(&format!("  {}\n", before[i]));
+            i += 1;
+            j += 1;
+        } else if i < before.len() && (j == after.len() || common[i + 1][j] >= common[i][j + 1]) {
+            out.push_str(&format!("- {}\n", before[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("+ {}\n", after[j]));
+            j += 1;
+        }
+    };
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Action;
+
+    #[test]
+    fn render_writes_parseable_sections() {
+        let query = Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Map],
+            vec![Action::Filter],
+            ]);
+        let rendered = render(&query);
+        assert_eq!(rendered, "# input\nName(\"a\")\nMap\nFilter\n# optimized\nName(\"a\")\nFilter\nMap\n");
+
+        let (input, optimized) = sections(&rendered).unwrap();
+        assert_eq!(parse::parse_query(input).unwrap(), query);
+        assert_eq!(parse::parse_query(optimized).unwrap(), query.optimize());
+    }
+
+    #[test]
+    fn sections_ignore_markers_inside_annotations() {
+        let mut query = Query::new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Map],
+            vec![Action::Filter],
+            ]);
+        query.annotate(1, "optimized by hand").unwrap();
+        query.annotate(2, "input").unwrap();
+        let rendered = render(&query);
+        assert!(rendered.contains("Map        # optimized by hand\n"));
+
+        let (input, optimized) = sections(&rendered).unwrap();
+        assert_eq!(parse::parse_query(input).unwrap(), query);
+        assert_eq!(parse::parse_query(optimized).unwrap(), query.optimize());
+        assert!(sections("Map  # optimized\n").is_err());
+    }
+
+    fn temp_path(name: &str) -> ::std::path::PathBuf {
+        env::temp_dir().join(format!("dataframes-{}-{}.plan", name, ::std::process::id()))
+    }
+
+    #[test]
+    fn assert_snapshot_writes_missing_snapshots_when_updating() {
+        let path = temp_path("snapshot-update");
+        let query = Query::new(vec![vec![Action::Name("a")], vec![Action::Map], vec![Action::Filter]]);
+        assert_snapshot_with(&path, &query, true);
+        let written = read(&path).unwrap();
+        assert_snapshot_with(&path, &query, false);
+        ::std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, render(&query));
+    }
+
+    #[test]
+    fn assert_snapshot_reports_mismatches() {
+        let path = temp_path("snapshot-mismatch");
+        let query = Query::new(vec![vec![Action::Name("a")], vec![Action::Map]]);
+        assert_snapshot_with(&path, &query, true);
+        let changed = Query::new(vec![vec![Action::Name("a")], vec![Action::Filter]]);
+        let result = ::std::panic::catch_unwind(|| assert_snapshot_with(&path, &changed, false));
+        let unchanged = read(&path).unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+
+        let message = result.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains("does not match"), "{}", message);
+        assert!(message.contains("- Map\n+ Filter\n"), "{}", message);
+        assert_eq!(unchanged, render(&query));
+    }
+
+    #[test]
+    fn line_diff_marks_changed_lines() {
+        assert_eq!(line_diff("a\nb\nc\n", "a\nc\nd\n"), "  a\n- b\n  c\n+ d\n");
+        assert_eq!(line_diff("a\nx\n", "a\ny\n"), "  a\n- x\n+ y\n");
+    }
+}
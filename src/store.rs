@@ -0,0 +1,283 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use parse::{self, ParseError};
+use Query;
+
+pub const MAGIC: &str = "dataframes plan";
+pub const VERSION: u32 = 4;
+
+/// `MIGRATIONS[i]` upgrades a grid written by version `i + 1` to one read
+/// by version `i + 2`.
+const MIGRATIONS: &[fn(&str) -> String] = &[
+    // 2 added Explode; version 1 grids read unchanged.
+    str::to_string,
+    // 3 added `# ...` step annotations; version 2 grids have none.
+    str::to_string,
+    // 4 added Having.
+    str::to_string,
+];
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    Manifest(String),
+    Parse(ParseError),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StoreError::Io(ref err) => write!(f, "{}", err),
+            StoreError::Manifest(ref message) => write!(f, "invalid manifest: {}", message),
+            StoreError::Parse(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> StoreError {
+        StoreError::Io(err)
+    }
+}
+
+impl From<ParseError> for StoreError {
+    fn from(err: ParseError) -> StoreError {
+        StoreError::Parse(err)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Manifest {
+    pub version: u32,
+    pub steps: usize,
+}
+
+/// A plan loaded from disk. `Query` borrows its names, so the file
+/// contents are kept here and the query is read out of them on demand.
+#[derive(Clone, Debug)]
+pub struct SavedPlan {
+    pub manifest: Manifest,
+    text: String,
+}
+
+impl SavedPlan {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SavedPlan, StoreError> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        let text = migrate(&text)?.into_owned();
+        let manifest = read(&text)?.0;
+        Ok(SavedPlan { manifest, text })
+    }
+
+    pub fn query<'s>(&'s self) -> Query<'s> {
+        read(&self.text).expect("plan was validated on load").1
+    }
+}
+
+pub fn is_saved_plan(text: &str) -> bool {
+    text.lines().next().map(|line| line.trim()) == Some(MAGIC)
+}
+
+pub fn write(query: &Query) -> String {
+    format!("{}\nversion: {}\nsteps: {}\n\n{}", MAGIC, VERSION, query.steps.len(), query.grid())
+}
+
+pub fn save<P: AsRef<Path>>(query: &Query, path: P) -> io::Result<()> {
+    File::create(path)?.write_all(write(query).as_bytes())
+}
+
+/// Reads a manifest header, terminated by a blank line, followed by a
+/// plan in the grid format. Plans written by older versions must go
+/// through `migrate` first.
+pub fn read<'a>(text: &'a str) -> Result<(Manifest, Query<'a>), StoreError> {
+    let (manifest, header_lines, body) = split(text)?;
+    if manifest.version < VERSION {
+        return Err(StoreError::Manifest(format!("version {} must be migrated first", manifest.version)))
+    }
+
+    let query = parse::parse_query(body).map_err(|mut err| {
+        err.line += header_lines;
+        err
+    })?;
+    if query.steps.len() != manifest.steps {
+        return Err(StoreError::Manifest(format!(
+            "expected {} steps, found {}", manifest.steps, query.steps.len())))
+    }
+    Ok((manifest, query))
+}
+
+/// Upgrades a saved plan to the current version, rewriting only its
+/// `version` line so that parse errors keep their line numbers.
+pub fn migrate(text: &str) -> Result<Cow<'_, str>, StoreError> {
+    let (manifest, _, body) = split(text)?;
+    if manifest.version == VERSION {
+        return Ok(Cow::Borrowed(text))
+    }
+
+    let header: String = text[..text.len() - body.len()].split_inclusive('\n').map(|line| {
+        if split_field(line.trim()).map(|(key, _)| key) == Some("version") {
+            format!("version: {}\n", VERSION)
+        } else {
+            line.to_string()
+        }
+    }).collect();
+    let mut body = body.to_string();
+    for migration in &MIGRATIONS[manifest.version as usize - 1..] {
+        body = migration(&body)
+    };
+    Ok(Cow::Owned(header + &body))
+}
+
+fn split(text: &str) -> Result<(Manifest, usize, &str), StoreError> {
+    if !is_saved_plan(text) {
+        return Err(StoreError::Manifest(format!("expected `{}` on the first line", MAGIC)))
+    }
+
+    let mut version = None;
+    let mut steps = None;
+    let mut header_lines = 0;
+    let mut body = "";
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        header_lines += 1;
+        offset += line.len();
+        let line = line.trim();
+        if line.is_empty() {
+            body = &text[offset..];
+            break
+        }
+        if line == MAGIC {
+            continue
+        }
+        let (key, value) = match split_field(line) {
+            Some(field) => field,
+            None => return Err(StoreError::Manifest(format!("expected `key: value`, found `{}`", line))),
+        };
+        match key {
+            "version" => version = Some(parse_field(key, value)?),
+            "steps" => steps = Some(parse_field(key, value)?),
+            _ => {},
+        }
+    };
+
+    let version = version.ok_or_else(|| StoreError::Manifest("missing `version`".to_string()))?;
+    if version == 0 || version > VERSION {
+        return Err(StoreError::Manifest(format!("unsupported version {}", version)))
+    }
+    let manifest = Manifest {
+        version,
+        steps: steps.ok_or_else(|| StoreError::Manifest("missing `steps`".to_string()))?,
+    };
+    Ok((manifest, header_lines, body))
+}
+
+fn split_field(line: &str) -> Option<(&str, &str)> {
+    line.find(':').map(|i| (line[..i].trim(), line[i + 1..].trim()))
+}
+
+fn parse_field<T: ::std::str::FromStr>(key: &str, value: &str) -> Result<T, StoreError> {
+    value.parse().map_err(|_| StoreError::Manifest(format!("invalid `{}`: `{}`", key, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use {Action, AnnotationError};
+
+    fn query() -> Query<'static> {
+        Query::new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Filter,    Action::Map],
+            vec![Action::Join("d"), Action::None,    Action::Name("d")],
+            ])
+    }
+
+    #[test]
+    fn can_save_and_load_a_plan() {
+        let path = env::temp_dir().join(format!("dataframes-store-{}.plan", ::std::process::id()));
+        query().save(&path).unwrap();
+        let saved = SavedPlan::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(saved.manifest, Manifest { version: VERSION, steps: 3 });
+        assert_eq!(saved.query(), query());
+    }
+
+    #[test]
+    fn annotations_survive_save_and_load() {
+        let path = env::temp_dir().join(format!("dataframes-annotations-{}.plan", ::std::process::id()));
+        let mut query = query();
+        query.annotate(1, "  from model orders ").unwrap();
+        assert_eq!(query.annotate(1, "dbt model # orders"), Err(AnnotationError::Unwritable));
+        assert_eq!(query.annotate(1, "line1\nline2"), Err(AnnotationError::Unwritable));
+        assert_eq!(query.annotate(1, "line1\rline2"), Err(AnnotationError::Unwritable));
+        assert_eq!(query.annotate(1, " "), Err(AnnotationError::Blank));
+        assert_eq!(query.annotate(10, "x"), Err(AnnotationError::NoSuchStep(10)));
+        query.save(&path).unwrap();
+        let saved = SavedPlan::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(saved.query().annotations(1), &["from model orders"]);
+        assert_eq!(saved.query(), query);
+    }
+
+    #[test]
+    fn read_checks_the_manifest() {
+        let text = write(&query());
+        assert!(text.starts_with("dataframes plan\nversion: 4\nsteps: 3\n\nName(\"a\")"));
+
+        let truncated = text.replace("steps: 3", "steps: 4");
+        match read(&truncated) {
+            Err(StoreError::Manifest(message)) => assert_eq!(message, "expected 4 steps, found 3"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let future = text.replace("version: 4", "version: 99");
+        assert!(read(&future).is_err());
+        assert!(read("Name(\"a\")").is_err());
+    }
+
+    #[test]
+    fn read_reports_parse_errors_at_file_lines() {
+        let text = "dataframes plan\nversion: 4\nsteps: 1\n\nMop\n";
+        match read(text) {
+            Err(StoreError::Parse(err)) => assert_eq!((err.line, err.column), (5, 1)),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn every_older_version_has_a_migration() {
+        assert_eq!(MIGRATIONS.len() as u32, VERSION - 1);
+    }
+
+    #[test]
+    fn migrate_loads_version_1_plans() {
+        let text = "dataframes plan\nversion: 1\nsteps: 3\nversion_note: old\n\n\
+                    Name(\"a\")  Name(\"b\")\n\
+                    Filter     Map\n\
+                    Join(\"d\")  None       Name(\"d\")\n";
+        match read(text) {
+            Err(StoreError::Manifest(message)) => assert_eq!(message, "version 1 must be migrated first"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let migrated = migrate(text).unwrap();
+        assert!(migrated.starts_with("dataframes plan\nversion: 4\nsteps: 3\nversion_note: old\n\n"));
+        let (manifest, query) = read(&migrated).unwrap();
+        assert_eq!(manifest, Manifest { version: VERSION, steps: 3 });
+        assert_eq!(query, self::query());
+
+        let current = write(&self::query());
+        assert!(matches!(migrate(&current).unwrap(), Cow::Borrowed(_)));
+    }
+}
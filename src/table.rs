@@ -0,0 +1,299 @@
+use std::env;
+use std::fmt;
+
+use {Action, Query};
+
+const ELLIPSIS: &str = "…";
+const MIN_CELL_WIDTH: usize = 4;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DisplayOptions {
+    /// Total width of the rendered table, borders included.
+    pub max_width: Option<usize>,
+    /// Steps beyond this are elided from the middle of the table.
+    pub max_rows: Option<usize>,
+    /// Writes one line per step listing only its non-None cells, for plans
+    /// too deep or wide to read as a grid.
+    pub compact: bool,
+}
+
+impl DisplayOptions {
+    /// Fits the table to the terminal width reported in `$COLUMNS`.
+    pub fn terminal() -> DisplayOptions {
+        let max_width = env::var("COLUMNS").ok().and_then(|cols| cols.parse().ok());
+        DisplayOptions { max_width, ..DisplayOptions::default() }
+    }
+}
+
+pub struct Table<'q, 'a: 'q> {
+    query: &'q Query<'a>,
+    options: DisplayOptions,
+}
+
+impl<'q, 'a> Table<'q, 'a> {
+    pub fn new(query: &'q Query<'a>, options: DisplayOptions) -> Table<'q, 'a> {
+        Table { query, options }
+    }
+
+    fn headers(&self, count: usize) -> Vec<String> {
+        (0..count).map(|i| {
+            match self.query.col(i).name() {
+                Some(name) => name.to_string(),
+                None => i.to_string(),
+            }
+        }).collect()
+    }
+
+    /// Each step's cells and its annotations, with elided steps as `None`.
+    fn rows(&self) -> Vec<Option<(Vec<String>, String)>> {
+        let rows = self.query.steps.iter().map(|step| {
+            let cells = step.actions.iter().map(|action| format!("{:?}", action)).collect();
+            Some((cells, annotations(&step.annotations)))
+        }).collect();
+        self.elide(rows)
+    }
+
+    fn elide<T>(&self, mut rows: Vec<Option<T>>) -> Vec<Option<T>> {
+        if let Some(max_rows) = self.options.max_rows {
+            if rows.len() > max_rows {
+                let tail = rows.split_off(rows.len() - max_rows / 2);
+                rows.truncate(max_rows - max_rows / 2);
+                rows.push(None);
+                rows.extend(tail);
+            }
+        }
+        rows
+    }
+
+    fn write_compact(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let headers = self.headers(self.query.width());
+        let digits = self.query.steps.len().saturating_sub(1).to_string().len();
+        let lines = self.query.steps.iter().enumerate().map(|(i, step)| {
+            let cells: Vec<String> = step.actions.iter().zip(&headers)
+                .filter(|&(action, _)| *action != Action::None)
+                .map(|(action, header)| format!("{}: {:?}", header, action))
+                .collect();
+            let line = format!("{:>3$}  {}  {}", i, cells.join("  "), annotations(&step.annotations), digits);
+            Some(line.trim_end().to_string())
+        }).collect();
+
+        for line in self.elide(lines) {
+            let line = line.unwrap_or_else(|| ELLIPSIS.to_string());
+            match self.options.max_width {
+                Some(max_width) => writeln!(f, "{}", truncate(&line, max_width.max(1)))?,
+                None => writeln!(f, "{}", line)?,
+            }
+        };
+        Ok(())
+    }
+
+    /// Drops columns from the right until the rest could fit `max_width`,
+    /// then shrinks the widest of them. Returns the number of columns kept.
+    fn fit(&self, widths: &mut Vec<usize>) -> usize {
+        let max_width = match self.options.max_width {
+            Some(max_width) => max_width,
+            None => return widths.len(),
+        };
+        let count = widths.len();
+        let total = |widths: &[usize], shrunk: bool| {
+            let ellipsis = if widths.len() < count { 4 } else { 0 };
+            let cells: usize = widths.iter()
+                .map(|&w| if shrunk { w.min(MIN_CELL_WIDTH) } else { w } + 3)
+                .sum();
+            cells + 1 + ellipsis
+        };
+
+        while widths.len() > 1 && total(widths, true) > max_width {
+            widths.pop();
+        };
+        while !widths.is_empty() && total(widths, false) > max_width {
+            let (i, &widest) = widths.iter().enumerate().max_by_key(|&(_, w)| w).unwrap();
+            if widest <= MIN_CELL_WIDTH {
+                break
+            }
+            widths[i] -= 1;
+        };
+        widths.len()
+    }
+}
+
+fn annotations(annotations: &[&str]) -> String {
+    let annotations: Vec<String> = annotations.iter().map(|annotation| format!("# {}", annotation)).collect();
+    annotations.join("  ")
+}
+
+fn truncate(cell: &str, width: usize) -> String {
+    if cell.chars().count() <= width {
+        return cell.to_string()
+    }
+    let kept: String = cell.chars().take(width - 1).collect();
+    format!("{}{}", kept, ELLIPSIS)
+}
+
+fn write_border(f: &mut fmt::Formatter, widths: &[usize], elided: bool,
+                chars: (&str, &str, &str)) -> fmt::Result {
+    let (left, middle, right) = chars;
+    let mut segments: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+    if elided {
+        segments.push("─".repeat(3));
+    }
+    writeln!(f, "{}{}{}", left, segments.join(middle), right)
+}
+
+fn write_row(f: &mut fmt::Formatter, widths: &[usize], elided: bool,
+             cells: &[String], note: &str) -> fmt::Result {
+    for (i, width) in widths.iter().enumerate() {
+        let cell = cells.get(i).map(|cell| truncate(cell, *width)).unwrap_or_default();
+        let padding = width - cell.chars().count();
+        write!(f, "│ {}{} ", cell, " ".repeat(padding))?
+    };
+    if elided {
+        let marker = if cells.len() > widths.len() { ELLIPSIS } else { " " };
+        write!(f, "│ {} ", marker)?
+    }
+    if note.is_empty() {
+        writeln!(f, "│")
+    } else {
+        writeln!(f, "│  {}", note)
+    }
+}
+
+impl<'q, 'a> fmt::Display for Table<'q, 'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.options.compact {
+            return self.write_compact(f)
+        }
+        let rows = self.rows();
+        let count = rows.iter().flat_map(|row| row.as_ref().map(|(cells, _)| cells.len())).max().unwrap_or(0);
+        let headers = self.headers(count);
+
+        let mut widths: Vec<usize> = headers.iter().map(|header| header.chars().count()).collect();
+        for (cells, _) in rows.iter().flat_map(|row| row.as_ref()) {
+            for (i, cell) in cells.iter().enumerate() {
+                widths[i] = widths[i].max(cell.chars().count())
+            }
+        };
+        let shown = self.fit(&mut widths);
+        let elided = shown < count;
+
+        write_border(f, &widths, elided, ("┌", "┬", "┐"))?;
+        write_row(f, &widths, elided, &headers, "")?;
+        write_border(f, &widths, elided, ("├", "┼", "┤"))?;
+        // Annotations sit to the right of the table in whatever width it
+        // leaves.
+        let table_width: usize = widths.iter().map(|w| w + 3).sum::<usize>() + 1 + if elided { 4 } else { 0 };
+        let note_width = self.options.max_width.map(|max_width| max_width.saturating_sub(table_width + 2));
+        for row in &rows {
+            match *row {
+                Some((ref cells, ref note)) => {
+                    let note = match note_width {
+                        Some(0) => String::new(),
+                        Some(width) => truncate(note, width),
+                        None => note.clone(),
+                    };
+                    write_row(f, &widths, elided, cells, &note)?
+                },
+                None => {
+                    let marker = vec![ELLIPSIS.to_string(); count];
+                    write_row(f, &widths, elided, &marker, "")?
+                },
+            }
+        };
+        write_border(f, &widths, elided, ("└", "┴", "┘"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Action;
+
+    fn query() -> Query<'static> {
+        Query::new(vec![
+            vec![Action::Name("a"), Action::Name("customer_id")],
+            vec![Action::Map,       Action::Filter],
+            vec![Action::Join("d"), Action::None,             Action::Name("d")],
+            vec![Action::Group(0),  Action::Select,           Action::Select],
+            ])
+    }
+
+    #[test]
+    fn renders_a_boxed_table_with_column_headers() {
+        assert_eq!(query().to_string(), "\
+┌───────────┬─────────────────────┬───────────┐
+│ a         │ customer_id         │ d         │
+├───────────┼─────────────────────┼───────────┤
+│ Name(\"a\") │ Name(\"customer_id\") │           │
+│ Map       │ Filter              │           │
+│ Join(\"d\") │ None                │ Name(\"d\") │
+│ Group(0)  │ Select              │ Select    │
+└───────────┴─────────────────────┴───────────┘
+")
+    }
+
+    #[test]
+    fn truncates_and_elides_columns_to_fit_the_width() {
+        let options = DisplayOptions { max_width: Some(20), ..DisplayOptions::default() };
+        assert_eq!(query().display(options).to_string(), "\
+┌───────┬──────┬───┐
+│ a     │ cus… │ … │
+├───────┼──────┼───┤
+│ Name… │ Nam… │   │
+│ Map   │ Fil… │   │
+│ Join… │ None │ … │
+│ Grou… │ Sel… │ … │
+└───────┴──────┴───┘
+")
+    }
+
+    #[test]
+    fn fits_an_empty_query_into_any_width() {
+        let options = DisplayOptions { max_width: Some(0), ..DisplayOptions::default() };
+        assert_eq!(Query::new(vec![]).display(options).to_string(), "┌┐\n│\n├┤\n└┘\n");
+    }
+
+    #[test]
+    fn elides_steps_beyond_max_rows() {
+        let options = DisplayOptions { max_rows: Some(2), ..DisplayOptions::default() };
+        let rendered = query().display(options).to_string();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert!(lines[3].starts_with("│ Name(\"a\")"));
+        assert!(lines[4].starts_with("│ …"));
+        assert!(lines[5].starts_with("│ Group(0)"));
+    }
+
+    #[test]
+    fn compact_mode_writes_one_line_per_step() {
+        let options = DisplayOptions { compact: true, ..DisplayOptions::default() };
+        assert_eq!(query().display(options).to_string(), "\
+0  a: Name(\"a\")  customer_id: Name(\"customer_id\")
+1  a: Map  customer_id: Filter
+2  a: Join(\"d\")  d: Name(\"d\")
+3  a: Group(0)  customer_id: Select  d: Select
+");
+
+        let options = DisplayOptions { compact: true, max_width: Some(16), max_rows: Some(2) };
+        assert_eq!(query().display(options).to_string(), "\
+0  a: Name(\"a\")…
+…
+3  a: Group(0) …
+");
+    }
+
+    #[test]
+    fn writes_annotations_beside_their_step() {
+        let mut query = query();
+        query.annotate(1, "from model orders").unwrap();
+        let rendered = query.display(DisplayOptions::default()).to_string();
+        assert_eq!(rendered.lines().nth(4).unwrap(), "│ Map       │ Filter              │           │  # from model orders");
+
+        let options = DisplayOptions { max_width: Some(56), ..DisplayOptions::default() };
+        let rendered = query.display(options).to_string();
+        assert!(rendered.lines().nth(4).unwrap().ends_with("│  # from…"));
+
+        let options = DisplayOptions { compact: true, ..DisplayOptions::default() };
+        let rendered = query.display(options).to_string();
+        assert_eq!(rendered.lines().nth(1).unwrap(), "1  a: Map  customer_id: Filter  # from model orders");
+    }
+}
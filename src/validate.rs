@@ -0,0 +1,237 @@
+use std::error::Error;
+use std::fmt;
+
+use {Action, Step};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShapeErrorKind {
+    UnnamedColumn,
+    EmptyStep,
+    Narrowed(usize),
+    WidenedWithoutJoin,
+    HavingWithoutGroup,
+    DuplicateName(usize),
+    UnprintableName,
+    TooManySteps(usize),
+    TooManyColumns(usize),
+}
+
+impl fmt::Display for ShapeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShapeErrorKind::UnnamedColumn => write!(f, "a new column must start with a Name"),
+            ShapeErrorKind::EmptyStep => write!(f, "step has no cells"),
+            ShapeErrorKind::Narrowed(previous) => {
+                write!(f, "step is narrower than the {} columns before it", previous)
+            },
+            ShapeErrorKind::WidenedWithoutJoin => write!(f, "only a Join step can add columns"),
+            ShapeErrorKind::HavingWithoutGroup => write!(f, "Having must come after a Group"),
+            ShapeErrorKind::DuplicateName(col) => write!(f, "name is already used by column {}", col),
+            ShapeErrorKind::UnprintableName => {
+                write!(f, "names cannot contain quotes, backslashes or control characters")
+            },
+            ShapeErrorKind::TooManySteps(max) => write!(f, "plan exceeds the limit of {} steps", max),
+            ShapeErrorKind::TooManyColumns(max) => {
+                write!(f, "step exceeds the limit of {} columns", max)
+            },
+        }
+    }
+}
+
+/// `col` is the first offending column, which for a narrowed step is the
+/// first one missing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShapeError {
+    pub step: usize,
+    pub col: usize,
+    pub kind: ShapeErrorKind,
+}
+
+impl fmt::Display for ShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "step {}, column {}: {}", self.step, self.col, self.kind)
+    }
+}
+
+impl Error for ShapeError {}
+
+/// Size limits for plans from untrusted sources. Optimization never adds
+/// steps or columns, so a plan within limits stays within them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Limits {
+    pub max_steps: Option<usize>,
+    pub max_cols: Option<usize>,
+}
+
+impl Limits {
+    /// Checks step number `step` (zero-based), `width` columns wide.
+    pub fn check_step(&self, step: usize, width: usize) -> Result<(), ShapeError> {
+        if let Some(max) = self.max_steps {
+            if step >= max {
+                return Err(ShapeError { step, col: 0, kind: ShapeErrorKind::TooManySteps(max) })
+            }
+        }
+        if let Some(max) = self.max_cols {
+            if width > max {
+                return Err(ShapeError { step, col: max, kind: ShapeErrorKind::TooManyColumns(max) })
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check(&self, step_vec: &[Vec<Action>]) -> Result<(), ShapeError> {
+        for (i, actions) in step_vec.iter().enumerate() {
+            self.check_step(i, actions.len())?
+        };
+        Ok(())
+    }
+}
+
+pub fn check_shape(steps: &[Step]) -> Result<(), ShapeError> {
+    let mut previous = 0;
+    let mut grouped = false;
+    let mut names = vec![];
+    for (i, step) in steps.iter().enumerate() {
+        let width = step.actions.len();
+        for (col, action) in step.actions.iter().enumerate() {
+            match *action {
+                Action::Name(name) | Action::Join(name) if !is_printable(name) => {
+                    return Err(ShapeError { step: i, col, kind: ShapeErrorKind::UnprintableName })
+                },
+                _ => {},
+            }
+        };
+        if !grouped {
+            if let Some(col) = step.actions.iter().position(|action| *action == Action::Having) {
+                return Err(ShapeError { step: i, col, kind: ShapeErrorKind::HavingWithoutGroup })
+            }
+        }
+        grouped = grouped || step.is_group();
+        if width < previous {
+            return Err(ShapeError { step: i, col: width, kind: ShapeErrorKind::Narrowed(previous) })
+        }
+        if width == 0 {
+            return Err(ShapeError { step: i, col: 0, kind: ShapeErrorKind::EmptyStep })
+        }
+        if width > previous && i > 0 && !step.is_join() {
+            return Err(ShapeError { step: i, col: previous, kind: ShapeErrorKind::WidenedWithoutJoin })
+        }
+        for (col, action) in step.actions.iter().enumerate().skip(previous) {
+            if let Action::Name(name) = *action {
+                if let Some(first) = names.iter().position(|&other| other == name) {
+                    return Err(ShapeError { step: i, col, kind: ShapeErrorKind::DuplicateName(first) })
+                }
+                names.push(name);
+                continue
+            }
+            return Err(ShapeError { step: i, col, kind: ShapeErrorKind::UnnamedColumn })
+        };
+        previous = width;
+    };
+    Ok(())
+}
+
+/// The grid writes names with `{:?}` and the parser reads them back
+/// without unescaping, so only names `Debug` leaves alone round-trip.
+fn is_printable(name: &str) -> bool {
+    format!("{:?}", name).len() == name.len() + 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse;
+    use Query;
+
+    #[test]
+    fn try_new_accepts_a_well_shaped_grid() {
+        assert!(Query::try_new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Map,       Action::Filter],
+            vec![Action::Join("d"), Action::None,    Action::Name("d")],
+            vec![Action::Group(0),  Action::Select,  Action::Empty],
+            ]).is_ok())
+    }
+
+    #[test]
+    fn try_new_reports_where_the_shape_breaks() {
+        let err = Query::try_new(vec![
+            vec![Action::Name("a"), Action::Map],
+            ]).unwrap_err();
+        assert_eq!(err, ShapeError { step: 0, col: 1, kind: ShapeErrorKind::UnnamedColumn });
+
+        let err = Query::try_new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Map],
+            ]).unwrap_err();
+        assert_eq!(err.to_string(), "step 1, column 1: step is narrower than the 2 columns before it");
+
+        let err = Query::try_new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Map,       Action::Name("b")],
+            ]).unwrap_err();
+        assert_eq!(err, ShapeError { step: 1, col: 1, kind: ShapeErrorKind::WidenedWithoutJoin });
+
+        let err = Query::try_new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Join("d"), Action::Name("d"), Action::None],
+            ]).unwrap_err();
+        assert_eq!(err, ShapeError { step: 1, col: 2, kind: ShapeErrorKind::UnnamedColumn });
+
+        let err = Query::try_new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::None,      Action::Having],
+            vec![Action::Group(0),  Action::None],
+            ]).unwrap_err();
+        assert_eq!(err, ShapeError { step: 1, col: 1, kind: ShapeErrorKind::HavingWithoutGroup });
+
+        let err = Query::try_new(vec![vec![], vec![]]).unwrap_err();
+        assert_eq!(err, ShapeError { step: 0, col: 0, kind: ShapeErrorKind::EmptyStep });
+        assert!(Query::try_new(vec![]).is_ok());
+
+        let err = Query::try_new(vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Join("b"), Action::None,    Action::Name("b")],
+            ]).unwrap_err();
+        assert_eq!(err.to_string(), "step 1, column 2: name is already used by column 1");
+
+        for name in &["a\"b", "a\\b", "a\tb"] {
+            let err = Query::try_new(vec![vec![Action::Name("a"), Action::Name(name)]]).unwrap_err();
+            assert_eq!(err, ShapeError { step: 0, col: 1, kind: ShapeErrorKind::UnprintableName });
+        }
+        let err = Query::try_new(vec![
+            vec![Action::Name("a")],
+            vec![Action::Join("d\n"), Action::Name("d")],
+            ]).unwrap_err();
+        assert_eq!(err, ShapeError { step: 1, col: 0, kind: ShapeErrorKind::UnprintableName });
+    }
+
+    #[test]
+    fn printable_names_round_trip_through_the_grid() {
+        let query = Query::try_new(vec![
+            vec![Action::Name("café"), Action::Name("a b#c")],
+            vec![Action::Join("d's"),  Action::None,         Action::Name("d's")],
+            ]).unwrap();
+        assert_eq!(parse::parse_query(&query.grid().to_string()).unwrap(), query);
+        assert!(parse::parse_query(&query.redacted()).is_ok());
+    }
+
+    #[test]
+    fn try_new_with_limits_rejects_oversized_plans() {
+        let steps = vec![
+            vec![Action::Name("a"), Action::Name("b")],
+            vec![Action::Map,       Action::Map],
+            vec![Action::Select,    Action::Select],
+            ];
+        let limits = Limits { max_steps: Some(2), ..Limits::default() };
+        let err = Query::try_new_with_limits(steps.clone(), &limits).unwrap_err();
+        assert_eq!(err, ShapeError { step: 2, col: 0, kind: ShapeErrorKind::TooManySteps(2) });
+
+        let limits = Limits { max_cols: Some(1), ..Limits::default() };
+        let err = Query::try_new_with_limits(steps.clone(), &limits).unwrap_err();
+        assert_eq!(err.to_string(), "step 0, column 1: step exceeds the limit of 1 columns");
+
+        let limits = Limits { max_steps: Some(3), max_cols: Some(2) };
+        assert!(Query::try_new_with_limits(steps, &limits).is_ok());
+    }
+}
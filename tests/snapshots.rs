@@ -0,0 +1,20 @@
+extern crate dataframes;
+
+use std::fs;
+use std::path::Path;
+
+use dataframes::snapshot;
+
+#[test]
+fn optimizer_snapshots_match() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+    let mut paths: Vec<_> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "plan"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let failures: Vec<String> = paths.iter().filter_map(|path| snapshot::check(path).err()).collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}